@@ -0,0 +1,488 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * resolver.rs - dependency resolution over a SummaryStream.
+ */
+
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::summary::{Summary, SummaryStream};
+use crate::version::base_name;
+
+/**
+ * A dependency pattern that could not be satisfied while resolving an
+ * install set, and why.
+ */
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub pattern: String,
+    pub reason: String,
+}
+
+/*
+ * Internal failure value threaded back up the search: the set of decision
+ * ids that contributed to the contradiction (for backjumping) plus a
+ * human-readable reason to surface if the whole search fails.
+ *
+ * `blame` is the *transitive* justification for the failure: every
+ * already-activated decision whose package directly conflicted, plus the
+ * decision whose DEPENDS/REQUIRES put the failing pattern on the worklist
+ * in the first place (recursively, since that parent decision is itself
+ * carried in the `Failure` it returns).  Without the latter, backjumping
+ * could skip straight past a decision that - had a different candidate
+ * been chosen - would never have required the failing pattern at all.
+ */
+struct Failure {
+    blame: HashSet<usize>,
+    reason: String,
+}
+
+/**
+ * Resolves an install set against the packages available in a
+ * [`SummaryStream`](crate::SummaryStream).
+ *
+ * `Resolver` indexes every entry by `pkgbase`, `pkgname`, and each
+ * `PROVIDES` token it offers, then performs a backtracking search that
+ * activates one candidate per requested/`DEPENDS`/`REQUIRES` pattern,
+ * satisfying a pattern either by a candidate's own `pkgbase`/`pkgversion`
+ * or by one of its `PROVIDES` tokens.  On a failed activation the full,
+ * transitive set of decisions that contributed to the contradiction -
+ * conflicting packages and the decision that required the failing
+ * pattern - is recorded against that pattern, so that backtracking can
+ * jump directly past the most recent decision implicated in the conflict
+ * rather than unwinding one frame at a time.  Once a full install set is
+ * found, the edges recorded between a decision and the dependency
+ * patterns it required are topologically sorted into the final
+ * dependencies-before-dependents order.
+ */
+pub struct Resolver<'a> {
+    summaries: Vec<&'a Summary>,
+    by_name: HashMap<&'a str, Vec<usize>>,
+    by_provides: HashMap<&'a str, Vec<usize>>,
+}
+
+impl<'a> Resolver<'a> {
+    /**
+     * Build a resolver over every [`Entry::Candidate`](crate::Entry::Candidate)
+     * in `stream`; incomplete/unsupported entries are not installable and
+     * are ignored.
+     */
+    pub fn new(stream: &'a SummaryStream) -> Resolver<'a> {
+        let summaries: Vec<&'a Summary> = stream.candidates().collect();
+        let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut by_provides: HashMap<&str, Vec<usize>> = HashMap::new();
+
+        for (i, sum) in summaries.iter().enumerate() {
+            by_name.entry(sum.pkgbase().as_str()).or_default().push(i);
+            by_name.entry(sum.pkgname().as_str()).or_default().push(i);
+            for p in sum.provides() {
+                by_provides.entry(p.as_str()).or_default().push(i);
+            }
+        }
+
+        Resolver {
+            summaries,
+            by_name,
+            by_provides,
+        }
+    }
+
+    /**
+     * Resolve an install set satisfying every pattern in `wanted`.
+     *
+     * On success the returned `Vec` is ordered so that dependencies always
+     * appear before the packages that depend on them.  On failure the
+     * [`Conflict`] describes the pattern that could not be satisfied.
+     */
+    pub fn resolve(&self, wanted: &[&str]) -> Result<Vec<&'a Summary>, Conflict> {
+        let mut decisions: Vec<usize> = Vec::new();
+        let mut activated: HashMap<usize, usize> = HashMap::new();
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+
+        let worklist: VecDeque<(String, Option<usize>)> =
+            wanted.iter().map(|s| (s.to_string(), None)).collect();
+
+        match self.solve(worklist, &mut decisions, &mut activated, &mut edges) {
+            Ok(()) => Ok(topo_order(&decisions, &edges)
+                .into_iter()
+                .map(|dec_id| self.summaries[decisions[dec_id]])
+                .collect()),
+            Err(failure) => Err(Conflict {
+                pattern: wanted.join(", "),
+                reason: failure.reason,
+            }),
+        }
+    }
+
+    /*
+     * Whether entry `idx` satisfies `pattern`: either its own
+     * pkgbase/pkgversion match it directly, or it `PROVIDES` a token equal
+     * to one of the pattern's brace-expanded base names (the other half of
+     * resolution, for virtual/library dependencies that no package is
+     * literally named after).
+     */
+    fn satisfies(&self, idx: usize, pattern: &str) -> bool {
+        if self.summaries[idx].matches_pattern(pattern) {
+            return true;
+        }
+        brace_alternatives(pattern).iter().any(|alt| {
+            let base = base_name(alt);
+            self.summaries[idx].provides().iter().any(|p| p == base)
+        })
+    }
+
+    /*
+     * Candidate summary indices for `pattern`: everything indexed under
+     * its literal pkgbase/PROVIDES prefix (or brace alternatives of it),
+     * which `satisfies` then filters with the real dewey-aware match.
+     */
+    fn candidates(&self, pattern: &str) -> Vec<usize> {
+        let mut found: Vec<usize> =
+            self.by_name.get(pattern).cloned().unwrap_or_default();
+
+        for base in brace_alternatives(pattern) {
+            let base = base_name(&base);
+            for &i in self.by_name.get(base).into_iter().flatten() {
+                if !found.contains(&i) {
+                    found.push(i);
+                }
+            }
+            for &i in self.by_provides.get(base).into_iter().flatten() {
+                if !found.contains(&i) {
+                    found.push(i);
+                }
+            }
+        }
+        found
+    }
+
+    /*
+     * Depth-first search with conflict-directed backjumping.
+     *
+     * `worklist` is the queue of still-unsatisfied dependency patterns,
+     * each tagged with the decision id whose DEPENDS/REQUIRES put it
+     * there (`None` for one of the top-level `wanted` patterns).  That
+     * parent id is folded into any `Failure` this pattern produces, so a
+     * contradiction downstream of a decision is always blamed on that
+     * decision too, not just on whatever it directly conflicted with -
+     * otherwise backjumping could skip past a decision whose *other*
+     * candidate would never have required the failing pattern at all.
+     *
+     * Activating a candidate pushes its own DEPENDS/REQUIRES to the front
+     * so they're resolved before its siblings, and records an edge from
+     * its own decision id to each dependency's (for the already-satisfied
+     * case, to whichever earlier decision satisfied it).  `resolve` then
+     * topologically sorts those edges into the final order, rather than
+     * relying on recursion order, since a dependency shared with a later
+     * sibling would otherwise end up nested - and thus ordered - under
+     * that sibling instead of its first requester.
+     */
+    fn solve(
+        &self,
+        mut worklist: VecDeque<(String, Option<usize>)>,
+        decisions: &mut Vec<usize>,
+        activated: &mut HashMap<usize, usize>,
+        edges: &mut Vec<(usize, usize)>,
+    ) -> Result<(), Failure> {
+        let (pattern, parent) = match worklist.pop_front() {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        /* Already satisfied by something we activated earlier. */
+        if let Some(&i) = activated.keys().find(|&&i| self.satisfies(i, &pattern)) {
+            if let Some(parent_id) = parent {
+                edges.push((parent_id, activated[&i]));
+            }
+            return self.solve(worklist, decisions, activated, edges);
+        }
+
+        let candidates = self.candidates(&pattern);
+        if candidates.is_empty() {
+            let mut blame = HashSet::new();
+            blame.extend(parent);
+            return Err(Failure {
+                blame,
+                reason: format!("nothing provides '{}'", pattern),
+            });
+        }
+
+        let mut blame: HashSet<usize> = HashSet::new();
+        let mut reason = format!("no candidate for '{}' was installable", pattern);
+
+        for cand in candidates {
+            if !self.satisfies(cand, &pattern) {
+                continue;
+            }
+
+            let mut conflicting_decisions: HashSet<usize> = HashSet::new();
+            for (&act_idx, &dec_id) in activated.iter() {
+                if conflicts(self.summaries[cand], self.summaries[act_idx])
+                    || conflicts(self.summaries[act_idx], self.summaries[cand])
+                {
+                    conflicting_decisions.insert(dec_id);
+                }
+            }
+            if !conflicting_decisions.is_empty() {
+                blame.extend(&conflicting_decisions);
+                continue;
+            }
+
+            let dec_id = decisions.len();
+            decisions.push(cand);
+            activated.insert(cand, dec_id);
+            let edges_len = edges.len();
+            if let Some(parent_id) = parent {
+                edges.push((parent_id, dec_id));
+            }
+
+            let mut sub_worklist = worklist.clone();
+            for req in self.summaries[cand].requires().iter().rev() {
+                sub_worklist.push_front((req.clone(), Some(dec_id)));
+            }
+            for dep in self.summaries[cand].depends().iter().rev() {
+                sub_worklist.push_front((dep.clone(), Some(dec_id)));
+            }
+
+            match self.solve(sub_worklist, decisions, activated, edges) {
+                Ok(()) => return Ok(()),
+                Err(failure) => {
+                    decisions.pop();
+                    activated.remove(&cand);
+                    edges.truncate(edges_len);
+
+                    if !failure.blame.contains(&dec_id) {
+                        /* Not our fault: jump straight past this decision. */
+                        return Err(failure);
+                    }
+                    let mut rest = failure.blame;
+                    rest.remove(&dec_id);
+                    blame.extend(rest);
+                    reason = failure.reason;
+                }
+            }
+        }
+
+        blame.extend(parent);
+        Err(Failure { blame, reason })
+    }
+}
+
+/*
+ * Topologically sort decision ids `0..decisions.len()` by `edges`
+ * (parent decision id -> dependency decision id) so that every
+ * dependency is emitted before the decision(s) that required it.
+ */
+fn topo_order(decisions: &[usize], edges: &[(usize, usize)]) -> Vec<usize> {
+    let mut children: HashMap<usize, Vec<usize>> = HashMap::new();
+    for &(parent, child) in edges {
+        children.entry(parent).or_default().push(child);
+    }
+
+    let mut visited: HashSet<usize> = HashSet::new();
+    let mut order: Vec<usize> = Vec::new();
+    for dec_id in 0..decisions.len() {
+        visit_decision(dec_id, &children, &mut visited, &mut order);
+    }
+    order
+}
+
+/* Post-order DFS helper for `topo_order`: visits dependencies before
+ * recording `dec_id` itself. */
+fn visit_decision(
+    dec_id: usize,
+    children: &HashMap<usize, Vec<usize>>,
+    visited: &mut HashSet<usize>,
+    order: &mut Vec<usize>,
+) {
+    if !visited.insert(dec_id) {
+        return;
+    }
+    for &child in children.get(&dec_id).into_iter().flatten() {
+        visit_decision(child, children, visited, order);
+    }
+    order.push(dec_id);
+}
+
+/*
+ * Expand a pattern's brace alternation (if any) into one pattern per
+ * alternative, so each can be indexed by its own base name.
+ */
+fn brace_alternatives(pattern: &str) -> Vec<String> {
+    if let Some(rest) = pattern.strip_prefix('{') {
+        if let Some(close) = rest.find('}') {
+            let (alts, tail) = rest.split_at(close);
+            let tail = &tail[1..];
+            return alts.split(',').map(|alt| format!("{}{}", alt, tail)).collect();
+        }
+    }
+    vec![pattern.to_string()]
+}
+
+/*
+ * Whether activating `candidate` is blocked by `other` via CONFLICTS or
+ * SUPERSEDES.
+ */
+fn conflicts(candidate: &Summary, other: &Summary) -> bool {
+    candidate.conflicts().iter().any(|c| other.matches_pattern(c))
+        || candidate.supersedes().iter().any(|s| other.matches_pattern(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::Entry;
+
+    fn make(pkgname: &str, depends: &[&str], conflicts: &[&str]) -> Summary {
+        let mut sum = Summary::new();
+        sum.parse_entry("BUILD_DATE", "2019-08-14 00:00:00 +0000")
+            .unwrap();
+        sum.parse_entry("CATEGORIES", "test").unwrap();
+        sum.parse_entry("COMMENT", "test package").unwrap();
+        sum.parse_entry("DESCRIPTION", "test package").unwrap();
+        sum.parse_entry("MACHINE_ARCH", "x86_64").unwrap();
+        sum.parse_entry("OPSYS", "Darwin").unwrap();
+        sum.parse_entry("OS_VERSION", "18.7.0").unwrap();
+        sum.parse_entry("PKGNAME", pkgname).unwrap();
+        sum.parse_entry("PKGPATH", "category/pkgtest").unwrap();
+        sum.parse_entry("PKGTOOLS_VERSION", "20190405").unwrap();
+        sum.parse_entry("SIZE_PKG", "1234").unwrap();
+        for d in depends {
+            sum.parse_entry("DEPENDS", d).unwrap();
+        }
+        for c in conflicts {
+            sum.parse_entry("CONFLICTS", c).unwrap();
+        }
+        sum
+    }
+
+    fn stream_of(summaries: Vec<Summary>) -> SummaryStream {
+        let mut stream = SummaryStream::new();
+        for s in summaries {
+            stream.entries_mut().push(Entry::Candidate(s));
+        }
+        stream
+    }
+
+    #[test]
+    fn resolves_transitive_dependency() {
+        let stream = stream_of(vec![
+            make("top-1.0", &["lib"], &[]),
+            make("lib-2.0", &[], &[]),
+        ]);
+        let resolver = Resolver::new(&stream);
+        let install = resolver.resolve(&["top-1.0"]).expect("should resolve");
+        assert_eq!(install.len(), 2);
+        assert_eq!(install[0].pkgname(), "lib-2.0");
+        assert_eq!(install[1].pkgname(), "top-1.0");
+    }
+
+    #[test]
+    fn orders_shared_dependency_before_every_dependent() {
+        /* top depends on [a, b], and b *also* depends on a; a must still
+         * come before both, not get nested under b's subtree and end up
+         * sorted after it. */
+        let stream = stream_of(vec![
+            make("top-1.0", &["a", "b"], &[]),
+            make("b-1.0", &["a"], &[]),
+            make("a-1.0", &[], &[]),
+        ]);
+        let resolver = Resolver::new(&stream);
+        let install = resolver.resolve(&["top-1.0"]).expect("should resolve");
+        assert_eq!(install.len(), 3);
+
+        let pos = |name: &str| install.iter().position(|s| s.pkgname() == name).unwrap();
+        assert!(pos("a-1.0") < pos("b-1.0"));
+        assert!(pos("a-1.0") < pos("top-1.0"));
+        assert!(pos("b-1.0") < pos("top-1.0"));
+    }
+
+    #[test]
+    fn resolves_dependency_via_provides() {
+        let mut impl_pkg = make("libfoo-impl-1.0", &[], &[]);
+        impl_pkg.parse_entry("PROVIDES", "libfoo").unwrap();
+        let stream = stream_of(vec![
+            make("top-1.0", &["libfoo"], &[]),
+            impl_pkg,
+        ]);
+        let resolver = Resolver::new(&stream);
+        let install = resolver.resolve(&["top-1.0"]).expect("should resolve via PROVIDES");
+        assert_eq!(install.len(), 2);
+        assert_eq!(install[0].pkgname(), "libfoo-impl-1.0");
+        assert_eq!(install[1].pkgname(), "top-1.0");
+    }
+
+    #[test]
+    fn resolves_requires_via_provides() {
+        let mut top = make("top-1.0", &[], &[]);
+        top.parse_entry("REQUIRES", "libfoo").unwrap();
+        let mut impl_pkg = make("libfoo-impl-1.0", &[], &[]);
+        impl_pkg.parse_entry("PROVIDES", "libfoo").unwrap();
+        let stream = stream_of(vec![top, impl_pkg]);
+        let resolver = Resolver::new(&stream);
+        let install = resolver.resolve(&["top-1.0"]).expect("should resolve via REQUIRES/PROVIDES");
+        assert_eq!(install.len(), 2);
+        assert_eq!(install[0].pkgname(), "libfoo-impl-1.0");
+        assert_eq!(install[1].pkgname(), "top-1.0");
+    }
+
+    #[test]
+    fn reports_conflict_between_dependencies() {
+        let stream = stream_of(vec![
+            make("a-1.0", &[], &["b"]),
+            make("b-1.0", &[], &[]),
+            make("top-1.0", &["a", "b"], &[]),
+        ]);
+        let resolver = Resolver::new(&stream);
+        let err = resolver.resolve(&["top-1.0"]).expect_err("a conflicts with b");
+        assert_eq!(err.pattern, "top-1.0");
+    }
+
+    #[test]
+    fn backjumps_past_decision_with_viable_alternative() {
+        /* top depends on [r, x]; r has one candidate.  x has two
+         * candidates: x-1.0 (tried first) depends on q, whose only
+         * candidate conflicts with r; x-2.0 has no dependencies at all.
+         * The q conflict should be blamed on *both* r (the direct
+         * conflict) and x-1.0 (whose DEPENDS put q on the worklist), so
+         * the search backtracks into trying x-2.0 instead of giving up
+         * the moment it sees the conflict didn't directly involve "x". */
+        let stream = stream_of(vec![
+            make("top-1.0", &["r", "x"], &[]),
+            make("r-1.0", &[], &[]),
+            make("x-1.0", &["q"], &[]),
+            make("x-2.0", &[], &[]),
+            make("q-1.0", &[], &["r"]),
+        ]);
+        let resolver = Resolver::new(&stream);
+        let install = resolver
+            .resolve(&["top-1.0"])
+            .expect("x-2.0 is a viable alternative that avoids the conflict");
+
+        let names: Vec<&str> = install.iter().map(|s| s.pkgname().as_str()).collect();
+        assert!(names.contains(&"r-1.0"));
+        assert!(names.contains(&"x-2.0"));
+        assert!(!names.contains(&"x-1.0"));
+        assert!(!names.contains(&"q-1.0"));
+    }
+
+    #[test]
+    fn reports_conflict_for_missing_dependency() {
+        let stream = stream_of(vec![make("top-1.0", &["missing"], &[])]);
+        let resolver = Resolver::new(&stream);
+        let err = resolver.resolve(&["top-1.0"]).expect_err("missing dep");
+        assert!(err.reason.contains("missing"));
+    }
+}
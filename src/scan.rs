@@ -0,0 +1,354 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * scan.rs - build a pkg_summary(5) database from a directory of binary
+ * packages, the equivalent of pkg_install's make-summary.
+ */
+
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tar::Archive;
+
+use crate::summary::{Entry, Summary, SummaryStream, VerifyError};
+
+/**
+ * Errors returned while building a `Summary` from a single binary package
+ * archive.
+ */
+#[derive(Debug)]
+pub enum ScanError {
+    /** The archive could not be opened or read. */
+    Io(std::io::Error),
+    /** The archive is missing a `+CONTENTS` metadata member. */
+    NoContents,
+    /** `+CONTENTS` has no `@name` directive to recover `PKGNAME` from. */
+    NoPkgname,
+    /** The resulting entry is missing a field `pkg_summary(5)` requires. */
+    Incomplete(&'static str),
+    /** Computing `FILE_SIZE`/`FILE_CKSUM` for the archive failed. */
+    Verify(VerifyError),
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScanError::Io(e) => write!(f, "I/O error: {}", e),
+            ScanError::NoContents => write!(f, "Missing +CONTENTS"),
+            ScanError::NoPkgname => write!(f, "+CONTENTS has no @name directive"),
+            ScanError::Incomplete(field) => write!(f, "{}", field),
+            ScanError::Verify(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ScanError {
+    fn from(e: std::io::Error) -> ScanError {
+        ScanError::Io(e)
+    }
+}
+
+impl From<VerifyError> for ScanError {
+    fn from(e: VerifyError) -> ScanError {
+        ScanError::Verify(e)
+    }
+}
+
+/*
+ * Is this member one of the metadata files we pull fields from, and if so
+ * which?  pkgsrc binary packages store these at the top of the archive
+ * alongside +CONTENTS, not under a package-named directory.
+ */
+fn metadata_key(name: &str) -> Option<&'static str> {
+    match name.trim_start_matches("./") {
+        "+BUILD_INFO" => Some("+BUILD_INFO"),
+        "+COMMENT" => Some("+COMMENT"),
+        "+DESC" => Some("+DESC"),
+        "+CONTENTS" => Some("+CONTENTS"),
+        _ => None,
+    }
+}
+
+/* +BUILD_INFO is VAR=value per line, the same shape as pkg_summary(5)
+ * itself, so its fields map directly onto Summary::parse_entry(). */
+fn apply_build_info(sum: &mut Summary, text: &str) {
+    for line in text.lines() {
+        if let Some((key, val)) = line.split_once('=') {
+            let _ = sum.parse_entry(key, val);
+        }
+    }
+}
+
+/* +CONTENTS is a PLIST: @directive lines followed by the installed file
+ * list.  We only need the @name directive (PKGNAME) and any
+ * @comment DEPENDS:pattern lines pkg_create records alongside it. */
+fn apply_contents(sum: &mut Summary, text: &str) -> Result<(), ScanError> {
+    let mut found_name = false;
+    for line in text.lines() {
+        if let Some(name) = line.strip_prefix("@name ") {
+            sum.parse_entry("PKGNAME", name.trim()).ok();
+            found_name = true;
+        } else if let Some(dep) = line.strip_prefix("@comment DEPENDS:") {
+            sum.parse_entry("DEPENDS", dep.trim()).ok();
+        }
+    }
+    if !found_name {
+        return Err(ScanError::NoPkgname);
+    }
+    Ok(())
+}
+
+/**
+ * Build a `Summary` from a single pkgsrc binary package archive
+ * (`*.tgz`/`*.tbz`), reading its `+BUILD_INFO`, `+COMMENT`, `+DESC` and
+ * `+CONTENTS` metadata members, then filling `FILE_NAME`, `FILE_SIZE` and
+ * `FILE_CKSUM` from the archive itself via
+ * [`Summary::compute_file_fields`].
+ *
+ * `path` must be a gzip- (`*.tgz`) or bzip2- (`*.tbz`) compressed tar
+ * archive; the decompressor is chosen from `path`'s extension, since gzip
+ * and bzip2 streams are not self-identifying.
+ */
+pub fn summary_from_package(path: &Path) -> Result<Summary, ScanError> {
+    let mut sum = Summary::new();
+    let mut have_contents = false;
+
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = match path.extension().and_then(|e| e.to_str()) {
+        Some("tbz") => Box::new(BzDecoder::new(file)),
+        _ => Box::new(GzDecoder::new(file)),
+    };
+    let mut archive = Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let key = match metadata_key(&name) {
+            Some(key) => key,
+            None => continue,
+        };
+
+        let mut text = String::new();
+        entry.read_to_string(&mut text)?;
+
+        match key {
+            "+BUILD_INFO" => apply_build_info(&mut sum, &text),
+            "+COMMENT" => sum.parse_entry("COMMENT", text.trim_end()).unwrap(),
+            "+DESC" => {
+                for line in text.lines() {
+                    sum.parse_entry("DESCRIPTION", line).unwrap();
+                }
+            }
+            "+CONTENTS" => {
+                apply_contents(&mut sum, &text)?;
+                have_contents = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if !have_contents {
+        return Err(ScanError::NoContents);
+    }
+
+    sum.parse_entry(
+        "FILE_NAME",
+        path.file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+            .as_str(),
+    )
+    .unwrap();
+    sum.compute_file_fields(path)?;
+
+    /*
+     * SIZE_PKG is the package's installed size, which +BUILD_INFO does not
+     * record; fall back to the archive's own size when nothing more
+     * precise was found among the metadata members.
+     */
+    if sum.size_pkg().is_none() {
+        sum.parse_entry("SIZE_PKG", &sum.file_size().to_string())
+            .unwrap();
+    }
+
+    sum.validate().map_err(ScanError::Incomplete)?;
+    Ok(sum)
+}
+
+/**
+ * Walk `dir` for `*.tgz`/`*.tbz` binary packages and build a
+ * [`SummaryStream`] of every one that yields a complete entry.
+ *
+ * Packages that fail to parse (missing metadata, an incomplete entry,
+ * I/O errors) are skipped rather than aborting the whole scan; use
+ * [`summary_from_package`] directly if you need to know why a particular
+ * package was rejected.
+ */
+pub fn scan_packages(dir: &Path) -> std::io::Result<SummaryStream> {
+    let mut stream = SummaryStream::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_package = matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("tgz") | Some("tbz")
+        );
+        if !is_package {
+            continue;
+        }
+
+        if let Ok(sum) = summary_from_package(&path) {
+            stream.entries_mut().push(Entry::Candidate(sum));
+        }
+    }
+
+    Ok(stream)
+}
+
+/**
+ * Write `stream` out as a `pkg_summary(5)` database, gzip-compressed as
+ * `pkg_summary.gz` conventionally is.
+ */
+pub fn write_summary_gz<W: Write>(stream: &SummaryStream, writer: W) -> std::io::Result<()> {
+    let mut encoder = GzEncoder::new(writer, Compression::default());
+    write!(encoder, "{}", stream)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/**
+ * Read a gzip-compressed `pkg_summary(5)` database (e.g. `pkg_summary.gz`)
+ * back into a [`SummaryStream`].
+ */
+pub fn read_summary_gz<R: Read>(reader: R) -> std::io::Result<SummaryStream> {
+    let mut stream = SummaryStream::new();
+    let mut decoder = GzDecoder::new(reader);
+    std::io::copy(&mut decoder, &mut stream)?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /* Build a minimal pkgsrc binary package archive in memory, mirroring
+     * what pkg_create would produce. */
+    fn make_package(pkgname: &str) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+
+        let mut add = |name: &str, data: &str| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data.as_bytes()).unwrap();
+        };
+
+        add(
+            "+BUILD_INFO",
+            "BUILD_DATE=2019-08-14 00:00:00 +0000\n\
+             CATEGORIES=test\n\
+             MACHINE_ARCH=x86_64\n\
+             OPSYS=Darwin\n\
+             OS_VERSION=18.7.0\n\
+             PKGPATH=category/pkgtest\n\
+             PKGTOOLS_VERSION=20190405\n",
+        );
+        add("+COMMENT", "A test package\n");
+        add("+DESC", "A test description.\nSecond line.\n");
+        add(
+            "+CONTENTS",
+            &format!("@comment $NetBSD$\n@name {}\nbin/test\n", pkgname),
+        );
+
+        builder.into_inner().unwrap()
+    }
+
+    fn write_gz_package(dir: &Path, file_name: &str, tar_bytes: &[u8]) -> PathBuf {
+        let path = dir.join(file_name);
+        let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        encoder.write_all(tar_bytes).unwrap();
+        encoder.finish().unwrap();
+        path
+    }
+
+    fn write_bz_package(dir: &Path, file_name: &str, tar_bytes: &[u8]) -> PathBuf {
+        let path = dir.join(file_name);
+        let mut encoder = bzip2::write::BzEncoder::new(
+            File::create(&path).unwrap(),
+            bzip2::Compression::default(),
+        );
+        encoder.write_all(tar_bytes).unwrap();
+        encoder.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn builds_summary_from_package_archive() {
+        let dir = std::env::temp_dir();
+        let path = write_gz_package(&dir, "pkgsrc-rs-test-scan.tgz", &make_package("pkgtest-1.0"));
+
+        let sum = summary_from_package(&path).expect("should scan");
+        assert_eq!(sum.pkgname(), "pkgtest-1.0");
+        assert_eq!(sum.comment(), "A test package");
+        assert_eq!(sum.description().len(), 2);
+        assert_eq!(sum.machine_arch(), "x86_64");
+        assert_eq!(sum.file_name(), "pkgsrc-rs-test-scan.tgz");
+        assert!(sum.file_size() > 0);
+        assert!(sum.verify_file(&dir).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn builds_summary_from_bz_package_archive() {
+        let dir = std::env::temp_dir();
+        let path = write_bz_package(&dir, "pkgsrc-rs-test-scan.tbz", &make_package("pkgtest-1.0"));
+
+        let sum = summary_from_package(&path).expect("should scan");
+        assert_eq!(sum.pkgname(), "pkgtest-1.0");
+        assert_eq!(sum.file_name(), "pkgsrc-rs-test-scan.tbz");
+        assert!(sum.verify_file(&dir).is_ok());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn scans_directory_and_round_trips_through_gz() {
+        let dir = std::env::temp_dir().join("pkgsrc-rs-test-scan-dir");
+        std::fs::create_dir_all(&dir).unwrap();
+        write_gz_package(&dir, "pkgtest-1.0.tgz", &make_package("pkgtest-1.0"));
+        write_gz_package(&dir, "otherpkg-2.0.tgz", &make_package("otherpkg-2.0"));
+        write_bz_package(&dir, "bzpkg-3.0.tbz", &make_package("bzpkg-3.0"));
+        std::fs::write(dir.join("not-a-package.txt"), b"ignore me").unwrap();
+
+        let stream = scan_packages(&dir).expect("should scan directory");
+        assert_eq!(stream.candidates().count(), 3);
+
+        let mut gz_bytes = Vec::new();
+        write_summary_gz(&stream, &mut gz_bytes).unwrap();
+
+        let reparsed = read_summary_gz(gz_bytes.as_slice()).unwrap();
+        assert_eq!(reparsed.candidates().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -16,7 +16,11 @@
  * summary.rs - handle pkg_summary(5) parsing.
  */
 
-use std::io::Write;
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use sha2::{Digest, Sha256, Sha512};
 
 #[cfg(test)]
 use unindent::unindent;
@@ -54,7 +58,34 @@ use unindent::unindent;
 #[derive(Debug)]
 pub struct SummaryStream {
     buf: Vec<u8>,
-    entries: Vec<Summary>,
+    entries: Vec<Entry>,
+    out_buf: Vec<u8>,
+    policy: ParsePolicy,
+    errors: Vec<ParseError>,
+    byte_offset: usize,
+    line_no: usize,
+}
+
+/**
+ * How a [`SummaryStream`] reacts to malformed input.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParsePolicy {
+    /** Abort the stream with an [`io::Error`](std::io::Error) on the first problem. */
+    Strict,
+    /** Record the problem in [`SummaryStream::errors`] and keep parsing. */
+    Tolerant,
+}
+
+/**
+ * A single problem encountered while streaming `pkg_summary(5)` input
+ * under [`ParsePolicy::Tolerant`], with its approximate location.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub offset: usize,
+    pub message: String,
 }
 
 /**
@@ -94,6 +125,51 @@ pub struct Summary {
     supersedes: Vec<String>,
 }
 
+/**
+ * Errors returned when verifying or computing a package file's checksum
+ * against its `Summary` entry.
+ */
+#[derive(Debug)]
+pub enum VerifyError {
+    /** `FILE_NAME` is not set on this entry. */
+    NoFileName,
+    /** `FILE_CKSUM` is not set on this entry. */
+    NoFileCksum,
+    /** `FILE_CKSUM` is not a recognised `<algorithm> <hexdigest>` pair. */
+    MalformedCksum,
+    /** The algorithm prefix in `FILE_CKSUM` is not supported. */
+    UnknownAlgorithm(String),
+    /** The package file could not be read. */
+    Io(std::io::Error),
+    /** The computed digest did not match `FILE_CKSUM`. */
+    Mismatch { expected: String, computed: String },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerifyError::NoFileName => write!(f, "Missing FILE_NAME"),
+            VerifyError::NoFileCksum => write!(f, "Missing FILE_CKSUM"),
+            VerifyError::MalformedCksum => write!(f, "Malformed FILE_CKSUM"),
+            VerifyError::UnknownAlgorithm(a) => {
+                write!(f, "Unsupported checksum algorithm: {}", a)
+            }
+            VerifyError::Io(e) => write!(f, "I/O error: {}", e),
+            VerifyError::Mismatch { expected, computed } => write!(
+                f,
+                "checksum mismatch: expected {}, computed {}",
+                expected, computed
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for VerifyError {
+    fn from(e: std::io::Error) -> VerifyError {
+        VerifyError::Io(e)
+    }
+}
+
 /*
  * XXX: Some are Strings, some are str due to unwrapping Option, I need to
  * figure out what's best here depending on how they will be used.
@@ -324,7 +400,9 @@ impl Summary {
             "DESCRIPTION" => self.description.push(valstring),
             "FILE_CKSUM" => self.file_cksum = Some(valstring),
             "FILE_NAME" => self.file_name = Some(valstring),
-            "FILE_SIZE" => self.file_size = Some(vali64.unwrap()),
+            "FILE_SIZE" => {
+                self.file_size = Some(vali64.map_err(|_| "invalid integer value")?)
+            }
             "HOMEPAGE" => self.homepage = Some(valstring),
             "LICENSE" => self.license = Some(valstring),
             "MACHINE_ARCH" => self.machine_arch = valstring,
@@ -344,7 +422,9 @@ impl Summary {
             "PREV_PKGPATH" => self.prev_pkgpath = Some(valstring),
             "PROVIDES" => self.provides.push(valstring),
             "REQUIRES" => self.requires.push(valstring),
-            "SIZE_PKG" => self.size_pkg = Some(vali64.unwrap()),
+            "SIZE_PKG" => {
+                self.size_pkg = Some(vali64.map_err(|_| "invalid integer value")?)
+            }
             "SUPERSEDES" => self.supersedes.push(valstring),
             _ => return Err("Unhandled key"),
         }
@@ -367,74 +447,385 @@ impl Summary {
      * Ensure all required fields (as per pkg_summary(5)) are set.
      */
     pub fn validate(&self) -> Result<(), &'static str> {
+        self.validate_detailed().map_err(MissingField::as_str)
+    }
+
+    /*
+     * As validate(), but identifies which field is missing so that callers
+     * classifying entries (e.g. SummaryStream::write) can record why.
+     */
+    fn validate_detailed(&self) -> Result<(), MissingField> {
         /*
          * Again, there's probably a fancy way to match these.
          */
         if self.build_date.is_empty() {
-            return Err("Missing BUILD_DATE");
+            return Err(MissingField::BuildDate);
         }
         if self.categories.is_empty() {
-            return Err("Missing CATEGORIES");
+            return Err(MissingField::Categories);
         }
         if self.comment.is_empty() {
-            return Err("Missing COMMENT");
+            return Err(MissingField::Comment);
         }
         if self.description.is_empty() {
-            return Err("Missing DESCRIPTION");
+            return Err(MissingField::Description);
         }
         if self.machine_arch.is_empty() {
-            return Err("Missing MACHINE_ARCH");
+            return Err(MissingField::MachineArch);
         }
         if self.opsys.is_empty() {
-            return Err("Missing OPSYS");
+            return Err(MissingField::Opsys);
         }
         if self.os_version.is_empty() {
-            return Err("Missing OS_VERSION");
+            return Err(MissingField::OsVersion);
         }
         if self.pkgname.is_empty() {
-            return Err("Missing PKGNAME");
+            return Err(MissingField::Pkgname);
         }
         if self.pkgpath.is_empty() {
-            return Err("Missing PKGPATH");
+            return Err(MissingField::Pkgpath);
         }
         if self.pkgtools_version.is_empty() {
-            return Err("Missing PKGTOOLS_VERSION");
+            return Err(MissingField::PkgtoolsVersion);
         }
         /*
          * SIZE_PKG is a required field but a size of 0 is valid (meta-pkgs)
          * so it needs to be an Option().
          */
         if self.size_pkg.is_none() {
-            return Err("Missing SIZE_PKG");
+            return Err(MissingField::SizePkg);
         }
         Ok(())
     }
+
+    /**
+     * Render this entry back into `pkg_summary(5)` `KEY=value` format.
+     *
+     * Fields are emitted in the same order as they are declared on
+     * `Summary`, with `Vec<String>` fields (`CATEGORIES`, `CONFLICTS`,
+     * `DEPENDS`, `DESCRIPTION`, `PROVIDES`, `REQUIRES`, `SUPERSEDES`)
+     * repeating the key once per value, and the entry is terminated with a
+     * blank line.  [`validate()`](Summary::validate) is called first so
+     * that an incomplete record can never be emitted, keeping
+     * `parse -> emit -> parse` idempotent.
+     *
+     * ## Example
+     *
+     * ```
+     * use pkgsrc::Summary;
+     *
+     * let mut sum = Summary::new();
+     * sum.parse_entry("BUILD_DATE", "2019-08-14 00:00:00 +0000").unwrap();
+     * sum.parse_entry("CATEGORIES", "test").unwrap();
+     * sum.parse_entry("COMMENT", "This is a test").unwrap();
+     * sum.parse_entry("DESCRIPTION", "A test description").unwrap();
+     * sum.parse_entry("MACHINE_ARCH", "x86_64").unwrap();
+     * sum.parse_entry("OPSYS", "Darwin").unwrap();
+     * sum.parse_entry("OS_VERSION", "18.7.0").unwrap();
+     * sum.parse_entry("PKGNAME", "pkgtest-1.0").unwrap();
+     * sum.parse_entry("PKGPATH", "category/pkgtest").unwrap();
+     * sum.parse_entry("PKGTOOLS_VERSION", "20190405").unwrap();
+     * sum.parse_entry("SIZE_PKG", "1234").unwrap();
+     * assert!(sum.to_summary_string().is_ok());
+     * ```
+     */
+    pub fn to_summary_string(&self) -> Result<String, &'static str> {
+        self.validate()?;
+
+        let mut out = String::new();
+
+        macro_rules! line {
+            ($key:expr, $val:expr) => {
+                out.push_str($key);
+                out.push('=');
+                out.push_str($val);
+                out.push('\n');
+            };
+        }
+        macro_rules! lines {
+            ($key:expr, $vals:expr) => {
+                for v in $vals {
+                    line!($key, v.as_str());
+                }
+            };
+        }
+
+        line!("BUILD_DATE", self.build_date.as_str());
+        lines!("CATEGORIES", &self.categories);
+        line!("COMMENT", self.comment.as_str());
+        lines!("CONFLICTS", &self.conflicts);
+        lines!("DEPENDS", &self.depends);
+        lines!("DESCRIPTION", &self.description);
+        if let Some(v) = &self.file_cksum {
+            line!("FILE_CKSUM", v.as_str());
+        }
+        if let Some(v) = &self.file_name {
+            line!("FILE_NAME", v.as_str());
+        }
+        if let Some(v) = &self.file_size {
+            line!("FILE_SIZE", v.to_string().as_str());
+        }
+        if let Some(v) = &self.homepage {
+            line!("HOMEPAGE", v.as_str());
+        }
+        if let Some(v) = &self.license {
+            line!("LICENSE", v.as_str());
+        }
+        line!("MACHINE_ARCH", self.machine_arch.as_str());
+        line!("OPSYS", self.opsys.as_str());
+        line!("OS_VERSION", self.os_version.as_str());
+        if let Some(v) = &self.pkg_options {
+            line!("PKG_OPTIONS", v.as_str());
+        }
+        line!("PKGNAME", self.pkgname.as_str());
+        line!("PKGPATH", self.pkgpath.as_str());
+        line!("PKGTOOLS_VERSION", self.pkgtools_version.as_str());
+        if let Some(v) = &self.prev_pkgpath {
+            line!("PREV_PKGPATH", v.as_str());
+        }
+        lines!("PROVIDES", &self.provides);
+        lines!("REQUIRES", &self.requires);
+        if let Some(v) = &self.size_pkg {
+            line!("SIZE_PKG", v.to_string().as_str());
+        }
+        lines!("SUPERSEDES", &self.supersedes);
+
+        out.push('\n');
+
+        Ok(out)
+    }
+
+    /**
+     * Match this package's `pkgbase`/`pkgversion` against a `DEPENDS`
+     * pattern, e.g. `foo>=1.0<2.0` or `{foo,bar}>=1.0`.  See
+     * [`version::matches_pattern`](crate::version::matches_pattern) for
+     * the supported syntax.
+     */
+    pub fn matches_pattern(&self, pat: &str) -> bool {
+        crate::version::matches_pattern(&self.pkgbase, &self.pkgversion, pat)
+    }
+
+    /*
+     * Hash `path` with the named pkg_summary(5) FILE_CKSUM algorithm,
+     * returning the lowercase hex digest.
+     */
+    fn hash_file(path: &Path, algorithm: &str) -> Result<String, VerifyError> {
+        let mut f = std::fs::File::open(path)?;
+        match algorithm {
+            "SHA256" => {
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut f, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+            "SHA512" => {
+                let mut hasher = Sha512::new();
+                std::io::copy(&mut f, &mut hasher)?;
+                Ok(format!("{:x}", hasher.finalize()))
+            }
+            other => Err(VerifyError::UnknownAlgorithm(other.to_string())),
+        }
+    }
+
+    /**
+     * Verify that `FILE_NAME`, resolved under `root`, matches `FILE_CKSUM`.
+     *
+     * `FILE_CKSUM` is formatted as `<algorithm> <hexdigest>` (e.g.
+     * `SHA512 abc...`); the algorithm prefix is parsed and dispatched to
+     * the matching hasher (`SHA256`/`SHA512`).
+     */
+    pub fn verify_file(&self, root: &Path) -> Result<(), VerifyError> {
+        let file_name = self.file_name.as_ref().ok_or(VerifyError::NoFileName)?;
+        let cksum = self.file_cksum.as_ref().ok_or(VerifyError::NoFileCksum)?;
+
+        let mut parts = cksum.splitn(2, ' ');
+        let algorithm = parts.next().ok_or(VerifyError::MalformedCksum)?;
+        let expected = parts.next().ok_or(VerifyError::MalformedCksum)?;
+
+        let computed = Summary::hash_file(&root.join(file_name), algorithm)?;
+        if computed.eq_ignore_ascii_case(expected) {
+            Ok(())
+        } else {
+            Err(VerifyError::Mismatch {
+                expected: expected.to_string(),
+                computed,
+            })
+        }
+    }
+
+    /**
+     * Fill in `FILE_SIZE` and `FILE_CKSUM` from the binary package archive
+     * at `path`, hashing it with SHA512.
+     */
+    pub fn compute_file_fields(&mut self, path: &Path) -> Result<(), VerifyError> {
+        let meta = std::fs::metadata(path)?;
+        let digest = Summary::hash_file(path, "SHA512")?;
+
+        self.file_size = Some(meta.len() as i64);
+        self.file_cksum = Some(format!("SHA512 {}", digest));
+        Ok(())
+    }
+}
+
+/**
+ * A `pkg_summary(5)` field required by [`Summary::validate`] that was
+ * missing, as identified on an [`Entry::Incomplete`].
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissingField {
+    BuildDate,
+    Categories,
+    Comment,
+    Description,
+    MachineArch,
+    Opsys,
+    OsVersion,
+    Pkgname,
+    Pkgpath,
+    PkgtoolsVersion,
+    SizePkg,
+}
+
+impl MissingField {
+    fn as_str(self) -> &'static str {
+        match self {
+            MissingField::BuildDate => "Missing BUILD_DATE",
+            MissingField::Categories => "Missing CATEGORIES",
+            MissingField::Comment => "Missing COMMENT",
+            MissingField::Description => "Missing DESCRIPTION",
+            MissingField::MachineArch => "Missing MACHINE_ARCH",
+            MissingField::Opsys => "Missing OPSYS",
+            MissingField::OsVersion => "Missing OS_VERSION",
+            MissingField::Pkgname => "Missing PKGNAME",
+            MissingField::Pkgpath => "Missing PKGPATH",
+            MissingField::PkgtoolsVersion => "Missing PKGTOOLS_VERSION",
+            MissingField::SizePkg => "Missing SIZE_PKG",
+        }
+    }
+}
+
+impl fmt::Display for MissingField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/**
+ * How a parsed `pkg_summary(5)` entry was classified by
+ * [`SummaryStream`], so that a consumer can see why a record isn't
+ * directly installable rather than having it silently dropped.
+ *
+ * `Yanked`/`Superseded` variants are expected to join this enum once the
+ * crate processes `SUPERSEDES` relationships between entries in the same
+ * stream, rather than only exposing the raw field on `Summary`.
+ */
+#[derive(Debug)]
+pub enum Entry {
+    /** A complete record, usable as an install candidate. */
+    Candidate(Summary),
+    /** Parsed, but missing a field `pkg_summary(5)` requires. */
+    Incomplete(Summary, MissingField),
+    /** Parsed, but contains a key this crate does not understand. */
+    Unsupported(Summary, String),
+}
+
+impl Entry {
+    /**
+     * Borrow the underlying `Summary`, regardless of classification.
+     */
+    pub fn as_summary(&self) -> &Summary {
+        match self {
+            Entry::Candidate(s) => s,
+            Entry::Incomplete(s, _) => s,
+            Entry::Unsupported(s, _) => s,
+        }
+    }
+
+    /**
+     * Consume this `Entry`, returning the underlying `Summary` regardless
+     * of classification.
+     */
+    pub fn into_summary(self) -> Summary {
+        match self {
+            Entry::Candidate(s) => s,
+            Entry::Incomplete(s, _) => s,
+            Entry::Unsupported(s, _) => s,
+        }
+    }
+
+    /**
+     * Return the `Summary` only if this entry is a
+     * [`Candidate`](Entry::Candidate).
+     */
+    pub fn as_candidate(&self) -> Option<&Summary> {
+        match self {
+            Entry::Candidate(s) => Some(s),
+            _ => None,
+        }
+    }
 }
 
 impl SummaryStream {
     /**
-     * Return a new SummaryStream with default values.
+     * Return a new SummaryStream with default values.  Malformed input is
+     * tolerated: problems are recorded in [`errors()`](SummaryStream::errors)
+     * rather than aborting the stream.  Use [`strict()`](SummaryStream::strict)
+     * to abort on the first problem instead.
      */
     pub fn new() -> SummaryStream {
         SummaryStream {
             buf: vec![],
             entries: vec![],
+            out_buf: vec![],
+            policy: ParsePolicy::Tolerant,
+            errors: vec![],
+            byte_offset: 0,
+            line_no: 0,
         }
     }
 
     /**
-     * Return vector of parsed Summary records.
+     * Return a new SummaryStream with the given [`ParsePolicy`]: `true`
+     * for [`ParsePolicy::Strict`], `false` for [`ParsePolicy::Tolerant`].
      */
-    pub fn entries(&self) -> &Vec<Summary> {
+    pub fn strict(strict: bool) -> SummaryStream {
+        let mut stream = SummaryStream::new();
+        stream.policy = if strict {
+            ParsePolicy::Strict
+        } else {
+            ParsePolicy::Tolerant
+        };
+        stream
+    }
+
+    /**
+     * Return vector of classified parsed entries.
+     */
+    pub fn entries(&self) -> &Vec<Entry> {
         &self.entries
     }
 
     /**
-     * Return mutable vector of parsed Summary records.
+     * Return mutable vector of classified parsed entries.
      */
-    pub fn entries_mut(&mut self) -> &mut Vec<Summary> {
+    pub fn entries_mut(&mut self) -> &mut Vec<Entry> {
         &mut self.entries
     }
+
+    /**
+     * Iterate over the `Summary` of every entry classified as an
+     * [`Entry::Candidate`], i.e. usable as an install candidate.
+     */
+    pub fn candidates(&self) -> impl Iterator<Item = &Summary> {
+        self.entries.iter().filter_map(Entry::as_candidate)
+    }
+
+    /**
+     * Problems recorded while parsing under
+     * [`ParsePolicy::Tolerant`] (the default).
+     */
+    pub fn errors(&self) -> &Vec<ParseError> {
+        &self.errors
+    }
 }
 
 impl Write for SummaryStream {
@@ -453,19 +844,56 @@ impl Write for SummaryStream {
         self.buf.extend_from_slice(input);
 
         /*
-         * Look for the last complete pkg_summary(5) record, if there are none
-         * then go to the next input.
+         * Decode what we have so far.  Invalid UTF-8 (e.g. a stray binary
+         * byte) is either fatal (Strict) or, under Tolerant, the offending
+         * bytes are dropped and recorded as an error so a single bad
+         * sequence doesn't lose the rest of the stream.  A trailing
+         * incomplete multi-byte sequence is left alone, as it may be
+         * completed by the next write().
          */
-        let input_string = match std::str::from_utf8(&self.buf) {
-            Ok(s) => {
-                if let Some(last) = s.rfind("\n\n") {
-                    s.get(0..last + 2).unwrap()
-                } else {
-                    return Ok(input.len());
+        let full_text = loop {
+            match std::str::from_utf8(&self.buf) {
+                Ok(s) => break s.to_string(),
+                Err(e) => {
+                    let valid_up_to = e.valid_up_to();
+                    let bad_len = match e.error_len() {
+                        Some(bad_len) => bad_len,
+                        None => {
+                            break std::str::from_utf8(&self.buf[..valid_up_to])
+                                .unwrap()
+                                .to_string();
+                        }
+                    };
+                    if self.policy == ParsePolicy::Strict {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "invalid UTF-8 in pkg_summary(5) stream",
+                        ));
+                    }
+                    self.errors.push(ParseError {
+                        line: self.line_no + 1,
+                        offset: self.byte_offset + valid_up_to,
+                        message: "invalid UTF-8 byte sequence skipped".to_string(),
+                    });
+                    self.buf.drain(valid_up_to..valid_up_to + bad_len);
                 }
             }
-            _ => panic!("ERROR: Invalid pkg_summary(5) stream"),
         };
+        let full_text = if self.byte_offset == 0 {
+            full_text.trim_start_matches('\u{feff}')
+        } else {
+            full_text.as_str()
+        };
+
+        /*
+         * Look for the last complete pkg_summary(5) record, if there are none
+         * then go to the next input.
+         */
+        let last = match full_text.rfind("\n\n") {
+            Some(last) => last,
+            None => return Ok(input.len()),
+        };
+        let input_string = &full_text[0..last + 2];
 
         /*
          * We have at least one complete record, parse it and add to the vector
@@ -473,30 +901,67 @@ impl Write for SummaryStream {
          */
         for sum_entry in input_string.split_terminator("\n\n") {
             let mut sum = Summary::new();
+            let mut unsupported: Option<String> = None;
+
             for line in sum_entry.lines() {
-                let v: Vec<&str> = line.splitn(2, '=').collect();
-                let key = v.get(0);
-                let val = v.get(1);
-                if key.is_none() || val.is_none() {
-                    panic!("ERROR: Invalid pkg_summary(5) line");
+                self.line_no += 1;
+
+                let line = line.trim_end_matches('\r');
+                let trimmed_start = line.trim_start();
+                if trimmed_start.is_empty() || trimmed_start.starts_with('#') {
+                    continue;
                 }
-                match sum.parse_entry(key.unwrap(), val.unwrap()) {
-                    Ok(_) => {}
-                    Err(err) => {
-                        println!("PARSE ERROR: {}", err);
-                        println!("{:#?}", sum);
+
+                let v: Vec<&str> = line.splitn(2, '=').collect();
+                let (key, val) = match (v.first(), v.get(1)) {
+                    (Some(key), Some(val)) => (*key, val.trim_end()),
+                    _ => {
+                        let err = ParseError {
+                            line: self.line_no,
+                            offset: self.byte_offset,
+                            message: format!("line has no '=': {:?}", line),
+                        };
+                        if self.policy == ParsePolicy::Strict {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                err.message,
+                            ));
+                        }
+                        self.errors.push(err);
+                        continue;
                     }
+                };
+
+                if let Err(reason) = sum.parse_entry(key, val) {
+                    if reason == "Unhandled key" {
+                        if unsupported.is_none() {
+                            unsupported = Some(reason.to_string());
+                        }
+                        continue;
+                    }
+                    let err = ParseError {
+                        line: self.line_no,
+                        offset: self.byte_offset,
+                        message: format!("{}: {:?}", reason, line),
+                    };
+                    if self.policy == ParsePolicy::Strict {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            err.message,
+                        ));
+                    }
+                    self.errors.push(err);
                 }
             }
-            match sum.validate() {
-                Ok(_) => {
-                    self.entries.push(sum);
-                }
-                Err(err) => {
-                    println!("VALIDATE ERROR: {}", err);
-                    println!("{:#?}", sum);
-                }
-            }
+
+            let entry = match unsupported {
+                Some(reason) => Entry::Unsupported(sum, reason),
+                None => match sum.validate_detailed() {
+                    Ok(()) => Entry::Candidate(sum),
+                    Err(field) => Entry::Incomplete(sum, field),
+                },
+            };
+            self.entries.push(entry);
         }
 
         /*
@@ -505,6 +970,7 @@ impl Write for SummaryStream {
          * up having to do something with the existing data.  This seems to be
          * the best way to do it for now?
          */
+        self.byte_offset += input_string.len();
         self.buf = self.buf.split_off(input_string.len());
 
         Ok(input.len())
@@ -515,6 +981,43 @@ impl Write for SummaryStream {
     }
 }
 
+impl Read for SummaryStream {
+    /*
+     * Serialize candidates() back into pkg_summary(5) text the first time we're
+     * read from, then hand it out a chunk at a time, mirroring how write()
+     * buffers in the other direction.
+     */
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.out_buf.is_empty() {
+            let mut rendered = Vec::new();
+            for sum in self.candidates() {
+                if let Ok(s) = sum.to_summary_string() {
+                    rendered.extend_from_slice(s.as_bytes());
+                }
+            }
+            self.out_buf = rendered;
+        }
+        if self.out_buf.is_empty() {
+            return Ok(0);
+        }
+        let n = std::cmp::min(buf.len(), self.out_buf.len());
+        buf[..n].copy_from_slice(&self.out_buf[..n]);
+        self.out_buf = self.out_buf.split_off(n);
+        Ok(n)
+    }
+}
+
+impl fmt::Display for SummaryStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for sum in self.candidates() {
+            if let Ok(s) = sum.to_summary_string() {
+                write!(f, "{}", s)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -539,11 +1042,176 @@ mod tests {
 
         "#,
         );
-        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary);
+        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).unwrap();
         assert_eq!(pkgsummary.entries().len(), 1);
 
-        let mut pkgsum = Summary::new();
-        pkgsum = pkgsummary.entries_mut().pop().expect("invalid");
+        let pkgsum = pkgsummary.entries_mut().pop().expect("invalid").into_summary();
         assert_eq!(pkgsum.description().len(), 2);
     }
+
+    #[test]
+    fn round_trip_summary_string() {
+        let mut pkgsummary = SummaryStream::new();
+        let pkginfo = unindent(
+            r#"
+        BUILD_DATE=2019-08-14 00:00:00 +0000
+        CATEGORIES=test
+        COMMENT=This is a test
+        DESCRIPTION=A test description
+        DESCRIPTION=This is a multi-line field
+        MACHINE_ARCH=x86_64
+        OPSYS=Darwin
+        OS_VERSION=18.7.0
+        PKGNAME=pkgtest-1.0
+        PKGPATH=category/pkgtest
+        PKGTOOLS_VERSION=20190405
+        SIZE_PKG=1234
+
+        "#,
+        );
+        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).unwrap();
+
+        let emitted = pkgsummary.entries()[0].as_summary().to_summary_string().unwrap();
+
+        let mut reparsed = SummaryStream::new();
+        std::io::copy(&mut emitted.as_bytes(), &mut reparsed).unwrap();
+        assert_eq!(reparsed.entries().len(), 1);
+        assert_eq!(
+            reparsed.entries()[0].as_summary().pkgname(),
+            pkgsummary.entries()[0].as_summary().pkgname()
+        );
+
+        let reemitted = reparsed.entries()[0].as_summary().to_summary_string().unwrap();
+        assert_eq!(emitted, reemitted);
+    }
+
+    #[test]
+    fn verify_file_roundtrip() {
+        let dir = std::env::temp_dir();
+        let path = dir.join("pkgsrc-rs-test-verify-file.tgz");
+        std::fs::write(&path, b"not a real package, just test bytes").unwrap();
+
+        let mut sum = Summary::new();
+        sum.parse_entry("FILE_NAME", path.file_name().unwrap().to_str().unwrap())
+            .unwrap();
+        sum.compute_file_fields(&path).unwrap();
+        assert!(sum.verify_file(&dir).is_ok());
+
+        sum.file_cksum = Some("SHA512 0000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000".to_string());
+        assert!(matches!(
+            sum.verify_file(&dir),
+            Err(VerifyError::Mismatch { .. })
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn classifies_incomplete_and_unsupported_entries() {
+        let mut pkgsummary = SummaryStream::new();
+        let pkginfo = unindent(
+            r#"
+        CATEGORIES=test
+        COMMENT=This is a test
+        DESCRIPTION=Missing several required fields
+        PKGNAME=incomplete-1.0
+
+        BUILD_DATE=2019-08-14 00:00:00 +0000
+        CATEGORIES=test
+        COMMENT=This is a test
+        DESCRIPTION=Has a key we don't understand
+        MACHINE_ARCH=x86_64
+        NOT_A_REAL_KEY=bogus
+        OPSYS=Darwin
+        OS_VERSION=18.7.0
+        PKGNAME=unsupported-1.0
+        PKGPATH=category/pkgtest
+        PKGTOOLS_VERSION=20190405
+        SIZE_PKG=1234
+
+        "#,
+        );
+        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).unwrap();
+
+        assert_eq!(pkgsummary.entries().len(), 2);
+        assert_eq!(pkgsummary.candidates().count(), 0);
+        assert!(matches!(
+            pkgsummary.entries()[0],
+            Entry::Incomplete(_, MissingField::BuildDate)
+        ));
+        assert!(matches!(pkgsummary.entries()[1], Entry::Unsupported(_, _)));
+    }
+
+    #[test]
+    fn tolerant_stream_records_errors_and_skips_comments() {
+        let mut pkgsummary = SummaryStream::new();
+        let pkginfo = unindent(
+            r#"
+        # a comment before the record
+        BUILD_DATE=2019-08-14 00:00:00 +0000
+        this line has no equals sign
+        CATEGORIES=test
+        COMMENT=This is a test
+        DESCRIPTION=A test description
+        MACHINE_ARCH=x86_64
+        OPSYS=Darwin
+        OS_VERSION=18.7.0
+        PKGNAME=pkgtest-1.0
+        PKGPATH=category/pkgtest
+        PKGTOOLS_VERSION=20190405
+        SIZE_PKG=1234
+
+        "#,
+        );
+        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).unwrap();
+
+        assert_eq!(pkgsummary.entries().len(), 1);
+        assert_eq!(pkgsummary.candidates().count(), 1);
+        assert_eq!(pkgsummary.errors().len(), 1);
+        assert!(pkgsummary.errors()[0].message.contains("no '='"));
+    }
+
+    #[test]
+    fn tolerant_stream_records_error_for_bad_integer_field() {
+        let mut pkgsummary = SummaryStream::new();
+        let pkginfo = unindent(
+            r#"
+        BUILD_DATE=2019-08-14 00:00:00 +0000
+        CATEGORIES=test
+        COMMENT=This is a test
+        DESCRIPTION=A test description
+        MACHINE_ARCH=x86_64
+        OPSYS=Darwin
+        OS_VERSION=18.7.0
+        PKGNAME=pkgtest-1.0
+        PKGPATH=category/pkgtest
+        PKGTOOLS_VERSION=20190405
+        SIZE_PKG=abc
+
+        "#,
+        );
+        std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).unwrap();
+
+        assert_eq!(pkgsummary.entries().len(), 1);
+        assert_eq!(pkgsummary.candidates().count(), 0);
+        assert_eq!(pkgsummary.errors().len(), 1);
+        assert!(pkgsummary.errors()[0].message.contains("invalid integer value"));
+        assert!(matches!(
+            pkgsummary.entries()[0],
+            Entry::Incomplete(_, MissingField::SizePkg)
+        ));
+    }
+
+    #[test]
+    fn strict_stream_aborts_on_malformed_line() {
+        let mut pkgsummary = SummaryStream::strict(true);
+        let pkginfo = unindent(
+            r#"
+        BUILD_DATE=2019-08-14 00:00:00 +0000
+        this line has no equals sign
+
+        "#,
+        );
+        assert!(std::io::copy(&mut pkginfo.as_bytes(), &mut pkgsummary).is_err());
+    }
 }
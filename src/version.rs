@@ -0,0 +1,342 @@
+/*
+ * Copyright (c) 2019 Jonathan Perkin <jonathan@perkin.org.uk>
+ *
+ * Permission to use, copy, modify, and distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ *
+ * version.rs - pkgsrc dewey version comparison and DEPENDS pattern matching.
+ */
+
+use std::cmp::Ordering;
+
+/**
+ * Compare two pkgsrc version strings using dewey ordering.
+ *
+ * The version is scanned left to right into a list of integer weights: a
+ * run of digits becomes its numeric value, alphabetic suffixes are mapped
+ * to their dewey weight (`alpha` -3, `beta` -2, `pre`/`rc` -1, `pl` 0),
+ * and any other separator (`.`, `-`, `_`) introduces a zero-weight
+ * component boundary. Missing trailing components compare as `0`. A
+ * trailing `nbN` (`PKGREVISION`) is split off first and only compared
+ * once the rest of the version is equal.
+ *
+ * ## Example
+ *
+ * ```
+ * use pkgsrc::version::compare;
+ * use std::cmp::Ordering;
+ *
+ * assert_eq!(compare("1.0", "1.0"), Ordering::Equal);
+ * assert_eq!(compare("1.0", "1.1"), Ordering::Less);
+ * assert_eq!(compare("1.0nb1", "1.0nb2"), Ordering::Less);
+ * assert_eq!(compare("1.0alpha1", "1.0"), Ordering::Less);
+ * ```
+ */
+pub fn compare(a: &str, b: &str) -> Ordering {
+    let (a_main, a_rev) = split_revision(a);
+    let (b_main, b_rev) = split_revision(b);
+
+    let ord = compare_weights(&weights(a_main), &weights(b_main));
+    if ord != Ordering::Equal {
+        return ord;
+    }
+    a_rev.cmp(&b_rev)
+}
+
+fn compare_weights(a: &[i64], b: &[i64]) -> Ordering {
+    let len = a.len().max(b.len());
+    for i in 0..len {
+        let wa = a.get(i).copied().unwrap_or(0);
+        let wb = b.get(i).copied().unwrap_or(0);
+        let ord = wa.cmp(&wb);
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    Ordering::Equal
+}
+
+/* Split a trailing `nbN` (PKGREVISION) off a version string. */
+fn split_revision(version: &str) -> (&str, i64) {
+    if let Some(idx) = version.rfind("nb") {
+        let rev_digits = &version[idx + 2..];
+        if !rev_digits.is_empty()
+            && rev_digits.chars().all(|c| c.is_ascii_digit())
+        {
+            return (&version[..idx], rev_digits.parse().unwrap_or(0));
+        }
+    }
+    (version, 0)
+}
+
+fn suffix_weight(word: &str) -> i64 {
+    match word.to_ascii_lowercase().as_str() {
+        "alpha" => -3,
+        "beta" => -2,
+        "pre" | "rc" => -1,
+        "pl" => 0,
+        _ => 0,
+    }
+}
+
+/* Scan a version string (with its PKGREVISION already removed) into its
+ * dewey weights. */
+fn weights(version: &str) -> Vec<i64> {
+    let mut out = Vec::new();
+    let mut chars = version.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() {
+            let mut num = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() {
+                    num.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            out.push(num.parse::<i64>().unwrap_or(0));
+        } else if c.is_ascii_alphabetic() {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_alphabetic() {
+                    word.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            out.push(suffix_weight(&word));
+        } else {
+            chars.next();
+            out.push(0);
+        }
+    }
+
+    out
+}
+
+/**
+ * Relational operators usable in a `DEPENDS` pattern, e.g. the `>=` in
+ * `foo>=1.0`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Op {
+    fn matches(self, ord: Ordering) -> bool {
+        match self {
+            Op::Lt => ord == Ordering::Less,
+            Op::Le => ord != Ordering::Greater,
+            Op::Gt => ord == Ordering::Greater,
+            Op::Ge => ord != Ordering::Less,
+            Op::Eq => ord == Ordering::Equal,
+        }
+    }
+}
+
+/**
+ * Return the literal prefix of a `DEPENDS` pattern up to its first
+ * relational, glob, or brace-alternation syntax, i.e. the part that can
+ * be used to index packages by `pkgbase`/`PROVIDES`.
+ */
+pub fn base_name(pattern: &str) -> &str {
+    let end = pattern
+        .find(|c: char| "<>=[]{}*?".contains(c))
+        .unwrap_or(pattern.len());
+    pattern[..end].trim_end_matches('-')
+}
+
+/**
+ * Match a package's `pkgbase`/`pkgversion` against a `DEPENDS` pattern.
+ *
+ * Supports the syntax used throughout pkgsrc `DEPENDS` entries: exact
+ * `foo-1.0`, glob `foo-[0-9]*`, relational `foo>=1.0`, bounded
+ * `foo>=1.0<2.0`, and brace alternation `{foo,bar}>=1.0`.
+ */
+pub fn matches_pattern(pkgbase: &str, pkgversion: &str, pattern: &str) -> bool {
+    if let Some(rest) = pattern.strip_prefix('{') {
+        if let Some(close) = rest.find('}') {
+            let (alts, tail) = rest.split_at(close);
+            let tail = &tail[1..];
+            return alts
+                .split(',')
+                .any(|alt| {
+                    let expanded = format!("{}{}", alt, tail);
+                    matches_pattern(pkgbase, pkgversion, &expanded)
+                });
+        }
+    }
+
+    if pattern.contains('*') || pattern.contains('?') || pattern.contains('[') {
+        let full = format!("{}-{}", pkgbase, pkgversion);
+        return glob_match(pattern, &full);
+    }
+
+    if let Some(pos) = pattern.find(['<', '>', '=']) {
+        let base = &pattern[..pos];
+        if base != pkgbase {
+            return false;
+        }
+        let constraints = parse_constraints(&pattern[pos..]);
+        if constraints.is_empty() {
+            return false;
+        }
+        return constraints
+            .iter()
+            .all(|(op, ver)| op.matches(compare(pkgversion, ver)));
+    }
+
+    /* A bare pkgbase with no version part matches any version of it. */
+    if pattern == pkgbase {
+        return true;
+    }
+
+    pattern == format!("{}-{}", pkgbase, pkgversion)
+}
+
+/* Parse a run of relational constraints, e.g. ">=1.0<2.0" into
+ * [(Ge, "1.0"), (Lt, "2.0")]. */
+fn parse_constraints(s: &str) -> Vec<(Op, &str)> {
+    let mut out = Vec::new();
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let (op, after_op) = if let Some(r) = rest.strip_prefix(">=") {
+            (Op::Ge, r)
+        } else if let Some(r) = rest.strip_prefix("<=") {
+            (Op::Le, r)
+        } else if let Some(r) = rest.strip_prefix("==") {
+            (Op::Eq, r)
+        } else if let Some(r) = rest.strip_prefix('>') {
+            (Op::Gt, r)
+        } else if let Some(r) = rest.strip_prefix('<') {
+            (Op::Lt, r)
+        } else {
+            break;
+        };
+
+        let end = after_op
+            .find(['<', '>', '='])
+            .unwrap_or(after_op.len());
+        let (ver, remainder) = after_op.split_at(end);
+        out.push((op, ver));
+        rest = remainder;
+    }
+
+    out
+}
+
+/* Minimal shell-style glob matcher supporting `*`, `?` and `[...]`
+ * character classes (including `a-z` ranges and a leading `!`/`^` for
+ * negation). */
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    glob_match_at(&p, &t)
+}
+
+fn glob_match_at(p: &[char], t: &[char]) -> bool {
+    match p.first() {
+        None => t.is_empty(),
+        Some('*') => {
+            (0..=t.len()).any(|i| glob_match_at(&p[1..], &t[i..]))
+        }
+        Some('?') => !t.is_empty() && glob_match_at(&p[1..], &t[1..]),
+        Some('[') => {
+            let close = match p.iter().position(|&c| c == ']') {
+                Some(i) => i,
+                None => return p == t, // malformed class, treat '[' literally
+            };
+            if t.is_empty() {
+                return false;
+            }
+            if char_class_matches(&p[1..close], t[0]) {
+                glob_match_at(&p[close + 1..], &t[1..])
+            } else {
+                false
+            }
+        }
+        Some(&c) => !t.is_empty() && t[0] == c && glob_match_at(&p[1..], &t[1..]),
+    }
+}
+
+fn char_class_matches(class: &[char], c: char) -> bool {
+    let (negate, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+
+    let mut matched = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+
+    matched != negate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dewey_ordering() {
+        assert_eq!(compare("1.0", "1.0"), Ordering::Equal);
+        assert_eq!(compare("1.0", "1.1"), Ordering::Less);
+        assert_eq!(compare("1.10", "1.9"), Ordering::Greater);
+        assert_eq!(compare("1.0nb1", "1.0nb2"), Ordering::Less);
+        assert_eq!(compare("1.0nb2", "1.0nb1"), Ordering::Greater);
+        assert_eq!(compare("1.0alpha1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0beta1", "1.0alpha1"), Ordering::Greater);
+        assert_eq!(compare("1.0pre1", "1.0"), Ordering::Less);
+        assert_eq!(compare("1.0pl", "1.0"), Ordering::Equal);
+    }
+
+    #[test]
+    fn pattern_matching() {
+        assert!(matches_pattern("foo", "1.0", "foo-1.0"));
+        assert!(matches_pattern("foo", "1.0", "foo"));
+        assert!(!matches_pattern("foo", "1.0", "foo-2.0"));
+        assert!(matches_pattern("foo", "1.5", "foo>=1.0"));
+        assert!(!matches_pattern("foo", "0.5", "foo>=1.0"));
+        assert!(matches_pattern("foo", "1.5", "foo>=1.0<2.0"));
+        assert!(!matches_pattern("foo", "2.5", "foo>=1.0<2.0"));
+        assert!(matches_pattern("foo", "9", "foo-[0-9]*"));
+        assert!(matches_pattern("bar", "2.0", "{foo,bar}>=1.0"));
+        assert!(!matches_pattern("baz", "2.0", "{foo,bar}>=1.0"));
+    }
+
+    #[test]
+    fn base_name_strips_syntax() {
+        assert_eq!(base_name("foo>=1.0"), "foo");
+        assert_eq!(base_name("foo-[0-9]*"), "foo");
+        assert_eq!(base_name("foo-1.0"), "foo-1.0");
+    }
+}